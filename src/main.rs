@@ -1,130 +1,391 @@
 #[macro_use]
 extern crate itertools;
+extern crate rand;
+extern crate rayon;
 
-use std::error::Error;
+use rand::Rng;
+use rayon::prelude::*;
 use std::fmt;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
 
-struct Pixel {
-    colour: u8,
+#[derive(Debug, Clone)]
+struct Colour {
+    r: f64,
+    g: f64,
+    b: f64,
 }
 
-impl Pixel {
-    pub fn white() -> Pixel {
-        Pixel { colour: 255 }
+impl Colour {
+    pub fn new(r: f64, g: f64, b: f64) -> Colour {
+        Colour { r, g, b }
+    }
+
+    pub fn white() -> Colour {
+        Colour::new(1.0, 1.0, 1.0)
+    }
+
+    pub fn black() -> Colour {
+        Colour::new(0.0, 0.0, 0.0)
+    }
+
+    fn to_byte(channel: f64) -> u8 {
+        (channel.clamp(0.0, 1.0) * 255.0).round() as u8
+    }
+
+    pub fn bytes(&self) -> (u8, u8, u8) {
+        (
+            Colour::to_byte(self.r),
+            Colour::to_byte(self.g),
+            Colour::to_byte(self.b),
+        )
+    }
+
+    pub fn add(&self, other: &Colour) -> Colour {
+        Colour::new(self.r + other.r, self.g + other.g, self.b + other.b)
+    }
+
+    pub fn scale(&self, factor: f64) -> Colour {
+        Colour::new(self.r * factor, self.g * factor, self.b * factor)
     }
 
-    pub fn black() -> Pixel {
-        Pixel { colour: 0 }
+    pub fn mult(&self, other: &Colour) -> Colour {
+        Colour::new(self.r * other.r, self.g * other.g, self.b * other.b)
     }
 }
 
-impl fmt::Display for Pixel {
+impl fmt::Display for Colour {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.colour)
+        let (r, g, b) = self.bytes();
+        write!(f, "{} {} {}", r, g, b)
     }
 }
 
 struct Canvas {
-    data: Vec<Vec<Pixel>>,
+    data: Vec<Colour>,
     width: usize,
     height: usize,
 }
 
 impl Canvas {
     pub fn new(width: usize, height: usize) -> Canvas {
-        let mut data = Vec::with_capacity(height);
-        for _i in 0..height {
-            let mut row = Vec::with_capacity(width);
-            for _j in 0..width {
-                row.push(Pixel::white());
-            }
-            data.push(row);
-        }
-
         Canvas {
             width,
             height,
-            data,
+            data: vec![Colour::black(); width * height],
         }
     }
 
-    pub fn get(&self, x: usize, y: usize) -> Option<&Pixel> {
-        self.data.get(x).and_then(|ys| ys.get(y))
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some(y * self.width + x)
+        } else {
+            None
+        }
     }
 
-    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut Pixel> {
-        self.data.get_mut(x).and_then(|ys| ys.get_mut(y))
+    pub fn get(&self, x: usize, y: usize) -> Option<&Colour> {
+        self.index(x, y).map(|i| &self.data[i])
     }
+}
 
-    pub fn ink(&mut self, x: usize, y: usize, intensity: u8) -> bool {
-        if let Some(pixel) = self.get_mut(x, y) {
-            pixel.colour = intensity;
-            true
-        } else {
-            false
-        }
-    }
+enum PpmFormat {
+    Ascii,
+    Binary,
 }
 
-fn render_ppm(path: &Path, canvas: Canvas) {
+fn render_ppm(path: &Path, canvas: Canvas, format: PpmFormat) {
     let display = path.display();
 
-    let mut file = match File::create(&path) {
-        Err(e) => panic!("Could not create {}: {}", display, e.description()),
+    let mut file = match File::create(path) {
+        Err(e) => panic!("Could not create {}: {}", display, e),
         Ok(file) => file,
     };
 
-    write!(file, "P2\n{} {}\n255\n", canvas.width, canvas.height).unwrap();
-    for (x, y) in iproduct!(0..canvas.height, 0..canvas.width) {
-        write!(file, "{} ", canvas.get(x, y).unwrap()).unwrap();
+    match format {
+        PpmFormat::Ascii => {
+            write!(file, "P3\n{} {}\n255\n", canvas.width, canvas.height).unwrap();
+            for (y, x) in iproduct!(0..canvas.height, 0..canvas.width) {
+                write!(file, "{} ", canvas.get(x, y).unwrap()).unwrap();
+            }
+        }
+        PpmFormat::Binary => {
+            write!(file, "P6\n{} {}\n255\n", canvas.width, canvas.height).unwrap();
+            for (y, x) in iproduct!(0..canvas.height, 0..canvas.width) {
+                let (r, g, b) = canvas.get(x, y).unwrap().bytes();
+                file.write_all(&[r, g, b]).unwrap();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Material {
+    colour: Colour,
+    ambient: f64,
+    diffuse: f64,
+    specular: f64,
+    shininess: f64,
+    reflectivity: f64,
+    transparency: f64,
+    refractive_index: f64,
+}
+
+impl Material {
+    pub fn new(colour: Colour) -> Material {
+        Material {
+            colour,
+            ambient: 0.1,
+            diffuse: 0.9,
+            specular: 0.9,
+            shininess: 200.0,
+            reflectivity: 0.0,
+            transparency: 0.0,
+            refractive_index: 1.0,
+        }
+    }
+
+    pub fn with_ambient(&mut self, ambient: f64) -> &mut Self {
+        self.ambient = ambient;
+        self
+    }
+
+    pub fn with_diffuse(&mut self, diffuse: f64) -> &mut Self {
+        self.diffuse = diffuse;
+        self
+    }
+
+    pub fn with_specular(&mut self, specular: f64) -> &mut Self {
+        self.specular = specular;
+        self
+    }
+
+    pub fn with_shininess(&mut self, shininess: f64) -> &mut Self {
+        self.shininess = shininess;
+        self
+    }
+
+    pub fn with_reflectivity(&mut self, reflectivity: f64) -> &mut Self {
+        self.reflectivity = reflectivity;
+        self
+    }
+
+    pub fn with_transparency(&mut self, transparency: f64, refractive_index: f64) -> &mut Self {
+        self.transparency = transparency;
+        self.refractive_index = refractive_index;
+        self
     }
 }
 
+/// A single ray-surface intersection: the ray parameter `t` where it lands,
+/// the point and outward unit normal there, and the surface's material.
+#[derive(Debug, Clone)]
+struct Hit {
+    t: f64,
+    point: Vector,
+    normal: Vector,
+    material: Material,
+}
+
+/// Anything a ray can intersect. `hit` reports the nearest intersection in
+/// `(t_min, t_max)` along the ray, or `None` if the surface is missed.
+trait Hittable: Send + Sync {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit>;
+}
+
 #[derive(Debug)]
 struct Sphere {
     position: Vector,
     radius: f64,
+    material: Material,
+    motion: Option<(Vector, f64, f64)>,
 }
 
 impl Sphere {
-    pub fn new(position: Vector, radius: f64) -> Sphere {
-        Sphere { position, radius }
+    pub fn new(position: Vector, radius: f64, material: Material) -> Sphere {
+        Sphere {
+            position,
+            radius,
+            material,
+            motion: None,
+        }
+    }
+
+    /// Move the sphere linearly from its original centre to `target` over the
+    /// interval `time0..time1`, producing motion blur under a moving shutter.
+    pub fn with_motion(&mut self, target: Vector, time0: f64, time1: f64) -> &mut Self {
+        self.motion = Some((target, time0, time1));
+        self
+    }
+
+    /// The sphere's centre at shutter time `time`, interpolating linearly
+    /// between the two endpoints when it is in motion.
+    fn center(&self, time: f64) -> Vector {
+        match &self.motion {
+            None => self.position.clone(),
+            Some((target, time0, time1)) => {
+                let frac = (time - time0) / (time1 - time0);
+                let mut delta = target.clone();
+                delta.minus(&self.position);
+                delta.mult(frac);
+
+                let mut center = self.position.clone();
+                center.add(&delta);
+                center
+            }
+        }
     }
 
-    pub fn collides_with(&self, ray: &Ray) -> Vec<Vector> {
-        let mut l = ray.direction.clone();
-        l.normalise();
+    fn normal_at(&self, point: &Vector, center: &Vector) -> Vector {
+        let mut normal = point.clone();
+        normal.minus(center);
+        normal.normalise();
+        normal
+    }
+}
 
-        let c = &self.position;
-        let r = &self.radius;
-        let mut o = ray.origin.clone();
+impl Hittable for Sphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let center = self.center(ray.time);
 
-        let o_minus_c = o.minus(&c);
+        let mut oc = ray.origin.clone();
+        oc.minus(&center);
 
-        let indicator = l.dot(o_minus_c).powi(2) - (o_minus_c.magnitude().powi(2) - r.powi(2));
+        let a = ray.direction.dot(&ray.direction);
+        let b = ray.direction.dot(&oc);
+        let c = oc.dot(&oc) - self.radius.powi(2);
 
-        if indicator == 0.0 {
-            let d = -l.dot(o_minus_c);
-            vec![ray.shine_to(d)]
-        } else if indicator > 0.0 {
-            let d1 = (-l.dot(o_minus_c)) + indicator.sqrt();
-            let d2 = (-l.dot(o_minus_c)) - indicator.sqrt();
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
 
-            if 0.0 <= d1 && 0.0 <= d2 {
-                vec![ray.shine_to(d1.min(d2)), ray.shine_to(d1.max(d2))]
-            } else if d1 >= 0.0 {
-                vec![ray.shine_to(d1)]
-            } else if d2 >= 0.0 {
-                vec![ray.shine_to(d2)]
-            } else {
-                vec![]
+        let root = discriminant.sqrt();
+        let mut t = (-b - root) / a;
+        if t <= t_min || t >= t_max {
+            t = (-b + root) / a;
+            if t <= t_min || t >= t_max {
+                return None;
             }
-        } else {
-            vec![]
         }
+
+        let point = ray.shine_to(t);
+        let normal = self.normal_at(&point, &center);
+        Some(Hit {
+            t,
+            point,
+            normal,
+            material: self.material.clone(),
+        })
+    }
+}
+
+/// An infinite plane through `point` with outward unit normal `normal`.
+#[derive(Debug)]
+struct Plane {
+    point: Vector,
+    normal: Vector,
+    material: Material,
+}
+
+impl Plane {
+    pub fn new(point: Vector, normal: Vector, material: Material) -> Plane {
+        let mut normal = normal;
+        // `Vector::new` doesn't compute a real magnitude, so `normalise()` is a
+        // no-op unless we recompute it from the components first.
+        normal.n = ((normal.x * normal.x) + (normal.y * normal.y) + (normal.z * normal.z)).sqrt();
+        normal.normalise();
+        Plane {
+            point,
+            normal,
+            material,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let denom = ray.direction.dot(&self.normal);
+        if denom.abs() < EPSILON {
+            return None;
+        }
+
+        let mut to_plane = self.point.clone();
+        to_plane.minus(&ray.origin);
+        let t = to_plane.dot(&self.normal) / denom;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+
+        Some(Hit {
+            t,
+            point: ray.shine_to(t),
+            normal: self.normal.clone(),
+            material: self.material.clone(),
+        })
+    }
+}
+
+/// A triangle with vertices `a`, `b`, `c`, intersected by Möller–Trumbore.
+#[derive(Debug)]
+struct Triangle {
+    a: Vector,
+    b: Vector,
+    c: Vector,
+    material: Material,
+}
+
+impl Triangle {
+    pub fn new(a: Vector, b: Vector, c: Vector, material: Material) -> Triangle {
+        Triangle { a, b, c, material }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let mut edge1 = self.b.clone();
+        edge1.minus(&self.a);
+        let mut edge2 = self.c.clone();
+        edge2.minus(&self.a);
+
+        let mut h = ray.direction.clone();
+        h.cross(&edge2);
+        let det = edge1.dot(&h);
+        if det.abs() < EPSILON {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let mut s = ray.origin.clone();
+        s.minus(&self.a);
+        let u = inv_det * s.dot(&h);
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let mut q = s.clone();
+        q.cross(&edge1);
+        let v = inv_det * ray.direction.dot(&q);
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = inv_det * edge2.dot(&q);
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+
+        let mut normal = edge1.clone();
+        normal.cross(&edge2);
+        normal.normalise();
+
+        Some(Hit {
+            t,
+            point: ray.shine_to(t),
+            normal,
+            material: self.material.clone(),
+        })
     }
 }
 
@@ -141,10 +402,6 @@ impl Vector {
         Vector { x, y, z, n: 1.0 }
     }
 
-    pub fn new_with_length(x: f64, y: f64, z: f64, n: f64) -> Vector {
-        Vector { x, y, z, n }
-    }
-
     pub fn cross(&mut self, other: &Vector) -> &mut Self {
         let new_x = (self.y * other.z) - (self.z * other.y);
         let new_y = (self.z * other.x) - (self.x * other.z);
@@ -204,44 +461,148 @@ impl Vector {
         self.n
     }
 
-    pub fn set(&mut self, x: f64, y: f64, z: f64) -> &mut Self {
-        self.x = x;
-        self.y = y;
-        self.z = z;
+    /// Mirror this (incoming) direction about `normal`, returning `D - 2(D·N)N`.
+    pub fn reflect(&self, normal: &Vector) -> Vector {
+        let mut bounced = normal.clone();
+        bounced.mult(2.0 * self.dot(normal));
 
-        self.n = ((self.x * self.x) + (self.y * self.y) + (self.z * self.z)).sqrt();
-        self
+        let mut result = self.clone();
+        result.minus(&bounced);
+        result
     }
 
-    pub fn set_as(&mut self, other: &Vector) -> &mut Self {
-        self.x = other.x;
-        self.y = other.y;
-        self.z = other.z;
-        self.n = other.n;
+    /// Refract this (incoming, unit) direction through a surface of normal
+    /// `normal` given the ratio of incident to transmitted index. Returns
+    /// `None` under total internal reflection.
+    pub fn refract(&self, normal: &Vector, ni_over_nt: f64) -> Option<Vector> {
+        let dt = self.dot(normal);
+        let discriminant = 1.0 - ni_over_nt.powi(2) * (1.0 - dt.powi(2));
+        if discriminant <= 0.0 {
+            return None;
+        }
+
+        let mut a = self.clone();
+        let mut offset = normal.clone();
+        offset.mult(dt);
+        a.minus(&offset);
+        a.mult(ni_over_nt);
 
-        self
+        let mut b = normal.clone();
+        b.mult(discriminant.sqrt());
+
+        a.minus(&b);
+        Some(a)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Projection {
+    Perspective,
+    Orthographic,
+}
+
+/// The orthonormal frame and image-plane corners derived from a camera's
+/// placement, precomputed once per render so every primary ray is cheap.
+struct Basis {
+    origin: Vector,
+    lower_left: Vector,
+    horizontal: Vector,
+    vertical: Vector,
+    u: Vector,
+    v: Vector,
+    w: Vector,
+    lens_radius: f64,
+    focus_dist: f64,
+    projection: Projection,
+}
+
+impl Basis {
+    /// The primary ray through normalised image-plane coordinate `(s, t)`.
+    /// Perspective rays have their origin jittered on the lens disk for
+    /// defocus blur; orthographic rays are not jittered.
+    fn ray(&self, s: f64, t: f64, rng: &mut impl Rng) -> Ray {
+        let mut target = self.lower_left.clone();
+        let mut h = self.horizontal.clone();
+        h.mult(s);
+        target.add(&h);
+        let mut vt = self.vertical.clone();
+        vt.mult(t);
+        target.add(&vt);
+
+        match self.projection {
+            Projection::Perspective => {
+                let (lx, ly) = if self.lens_radius > 0.0 {
+                    random_in_unit_disk(rng)
+                } else {
+                    (0.0, 0.0)
+                };
+
+                let mut offset = self.u.clone();
+                offset.mult(self.lens_radius * lx);
+                let mut ov = self.v.clone();
+                ov.mult(self.lens_radius * ly);
+                offset.add(&ov);
+
+                let mut origin = self.origin.clone();
+                origin.add(&offset);
+
+                let mut direction = target.clone();
+                direction.minus(&origin);
+                Ray::new(origin, direction)
+            }
+            Projection::Orthographic => {
+                let mut origin = target.clone();
+                let mut back = self.w.clone();
+                back.mult(self.focus_dist);
+                origin.add(&back);
+
+                let mut direction = self.w.clone();
+                direction.mult(-1.0);
+                Ray::new(origin, direction)
+            }
+        }
     }
 }
 
 struct Camera {
-    plane_z: Vector,
-    plane_x: Vector,
-    pos: (f64, f64),
-    width: f64,
-    height: f64,
+    lookfrom: Vector,
+    lookat: Vector,
+    vup: Vector,
+    vfov: f64,
+    projection: Projection,
+    aperture: f64,
+    focus_dist: f64,
     pixels_width: usize,
     pixels_height: usize,
+    max_depth: usize,
+    threads: usize,
+    chunk_rows: usize,
+    samples: usize,
+    shutter_open: f64,
+    shutter_close: f64,
 }
 
 #[derive(Debug)]
 struct Ray {
     direction: Vector,
     origin: Vector,
+    time: f64,
 }
 
 impl Ray {
     pub fn new(origin: Vector, direction: Vector) -> Ray {
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            time: 0.0,
+        }
+    }
+
+    /// Stamp the ray with the shutter time at which it is cast, used to place
+    /// moving surfaces.
+    pub fn with_time(mut self, time: f64) -> Ray {
+        self.time = time;
+        self
     }
 
     pub fn shine_to(&self, distance: f64) -> Vector {
@@ -256,92 +617,358 @@ impl Ray {
 
 const EPSILON: f64 = 0.000000001;
 
+/// Schlick's approximation of the Fresnel reflectance for a ray hitting a
+/// surface of the given refractive index at the given angle.
+fn schlick(cosine: f64, refractive_index: f64) -> f64 {
+    let r0 = ((1.0 - refractive_index) / (1.0 + refractive_index)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+}
+
+/// Rejection-sample a point in the unit disk, used to jitter the ray origin
+/// across the lens for defocus blur.
+fn random_in_unit_disk(rng: &mut impl Rng) -> (f64, f64) {
+    loop {
+        let x = 2.0 * rng.gen::<f64>() - 1.0;
+        let y = 2.0 * rng.gen::<f64>() - 1.0;
+        if x * x + y * y < 1.0 {
+            return (x, y);
+        }
+    }
+}
+
 impl Camera {
-    pub fn new(width: f64, height: f64, pixels_width: usize, pixels_height: usize) -> Camera {
-        let default_z = Vector::new(0.0, 0.0, 1.0);
-        let default_x = Vector::new(1.0, 0.0, 0.0);
+    pub fn new(lookfrom: Vector, lookat: Vector, vfov: f64, pixels_width: usize, pixels_height: usize) -> Camera {
         Camera {
-            plane_z: default_z,
-            plane_x: default_x,
-            pos: (0.0, 0.0),
-            width,
-            height,
+            lookfrom,
+            lookat,
+            vup: Vector::new(0.0, 1.0, 0.0),
+            vfov,
+            projection: Projection::Perspective,
+            aperture: 0.0,
+            focus_dist: 1.0,
             pixels_width,
             pixels_height,
+            max_depth: 10,
+            threads: 0,
+            chunk_rows: 16,
+            samples: 1,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
         }
     }
 
-    pub fn with_pos(&mut self, x: f64, y: f64) -> &mut Self {
-        self.pos = (x, y);
+    pub fn with_vup(&mut self, vup: Vector) -> &mut Self {
+        self.vup = vup;
         self
     }
 
-    pub fn with_plane_z(&mut self, vec: Vector) -> &mut Self {
-        self.plane_z = vec;
+    pub fn with_projection(&mut self, projection: Projection) -> &mut Self {
+        self.projection = projection;
         self
     }
 
-    pub fn with_plane_x(&mut self, vec: Vector) -> &mut Self {
-        self.plane_x = vec;
+    /// Enable thin-lens defocus blur: rays fan from a disk of diameter
+    /// `aperture` and converge at `focus_dist`, so surfaces off that plane
+    /// blur. An orthographic camera ignores `aperture` and uses `focus_dist`
+    /// only to size the image plane.
+    pub fn with_focus(&mut self, aperture: f64, focus_dist: f64) -> &mut Self {
+        self.aperture = aperture;
+        self.focus_dist = focus_dist;
         self
     }
 
-    fn cast_ray(ray: &Ray, light_distance: f64, objects: &Vec<Sphere>) -> u8 {
-        for object in objects.iter() {
-            let collisions = object.collides_with(&ray);
+    pub fn with_max_depth(&mut self, max_depth: usize) -> &mut Self {
+        self.max_depth = max_depth;
+        self
+    }
 
-            for mut collision in collisions {
-                let collision_distance = collision.minus(&ray.origin).magnitude();
-                if collision_distance > EPSILON && collision_distance < light_distance {
-                    return 0;
-                }
-            }
+    /// Number of worker threads to render with. Zero (the default) leaves the
+    /// choice to rayon's global pool.
+    pub fn with_threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+
+    /// How many pixel rows make up one parallel tile.
+    pub fn with_chunk_rows(&mut self, chunk_rows: usize) -> &mut Self {
+        self.chunk_rows = chunk_rows;
+        self
+    }
+
+    /// Number of jittered rays fired per pixel for anti-aliasing. One (the
+    /// default) keeps the single centred ray per pixel.
+    pub fn with_samples(&mut self, samples: usize) -> &mut Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Shutter open and close times. Each primary ray is stamped with a time
+    /// sampled uniformly in this interval, so moving surfaces blur. The
+    /// default open == close freezes the scene.
+    pub fn with_shutter(&mut self, open: f64, close: f64) -> &mut Self {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    /// Build the orthonormal frame and image-plane corners for the current
+    /// placement: w = normalize(lookfrom - lookat), u = normalize(vup x w),
+    /// v = w x u.
+    fn basis(&self) -> Basis {
+        let aspect = (self.pixels_width as f64) / (self.pixels_height as f64);
+        let theta = self.vfov.to_radians();
+        let half_height = (theta / 2.0).tan() * self.focus_dist;
+        let half_width = aspect * half_height;
+
+        let mut w = self.lookfrom.clone();
+        w.minus(&self.lookat);
+        w.normalise();
+
+        let mut u = self.vup.clone();
+        u.cross(&w);
+        u.normalise();
+
+        let mut v = w.clone();
+        v.cross(&u);
+
+        let mut lower_left = self.lookfrom.clone();
+        let mut du = u.clone();
+        du.mult(half_width);
+        lower_left.minus(&du);
+        let mut dv = v.clone();
+        dv.mult(half_height);
+        lower_left.minus(&dv);
+        let mut dw = w.clone();
+        dw.mult(self.focus_dist);
+        lower_left.minus(&dw);
+
+        let mut horizontal = u.clone();
+        horizontal.mult(2.0 * half_width);
+        let mut vertical = v.clone();
+        vertical.mult(2.0 * half_height);
+
+        Basis {
+            origin: self.lookfrom.clone(),
+            lower_left,
+            horizontal,
+            vertical,
+            u,
+            v,
+            w,
+            lens_radius: self.aperture / 2.0,
+            focus_dist: self.focus_dist,
+            projection: self.projection,
         }
+    }
 
-        255 - (25 * (light_distance as u8)) // TODO make something better
+    /// True if anything lies between `point` and the light, casting `point`
+    /// into shadow for that light.
+    fn occluded(point: &Vector, direction: &Vector, light_distance: f64, objects: &[Box<dyn Hittable>], time: f64) -> bool {
+        let ray = Ray::new(point.clone(), direction.clone()).with_time(time);
+        Camera::intersect(&ray, objects, EPSILON, light_distance).is_some()
     }
 
-    pub fn raytrace(&self, objects: &Vec<Sphere>, lights: &Vec<Light>) -> Canvas {
-        let mut canvas = Canvas::new(self.pixels_width, self.pixels_height);
+    /// The nearest surface the ray meets in `(t_min, t_max)`, found by walking
+    /// every object and keeping the smallest valid `t` so the closest surface
+    /// wins regardless of object order.
+    fn intersect(ray: &Ray, objects: &[Box<dyn Hittable>], t_min: f64, t_max: f64) -> Option<Hit> {
+        let mut closest = t_max;
+        let mut nearest = None;
 
-        let mut plane_y = self.plane_z.clone();
-        plane_y.cross(&self.plane_x);
+        for object in objects.iter() {
+            if let Some(hit) = object.hit(ray, t_min, closest) {
+                closest = hit.t;
+                nearest = Some(hit);
+            }
+        }
 
-        let width_step = self.width / (self.pixels_width as f64);
-        let height_step = self.height / (self.pixels_height as f64);
+        nearest
+    }
 
-        let mut ray = Ray::new(Vector::new(0.0, 0.0, 0.0), self.plane_z.clone());
-        let mut offset_x = self.plane_x.clone();
-        let mut offset_y = plane_y.clone();
+    /// Trace a ray into the scene and return the colour it carries back,
+    /// recursing through reflective and transparent surfaces until `depth`
+    /// reaches zero or the ray escapes to the background.
+    fn trace(&self, ray: &Ray, depth: usize, objects: &[Box<dyn Hittable>], lights: &[Light]) -> Colour {
+        if depth == 0 {
+            return Colour::black();
+        }
 
-        for (x, y) in iproduct!(0..self.pixels_width, 0..self.pixels_height) {
-            ray.origin.add(offset_x.mult((x as f64) * width_step));
-            ray.origin.add(offset_y.mult((y as f64) * height_step));
+        let hit = match Camera::intersect(ray, objects, EPSILON, f64::INFINITY) {
+            Some(hit) => hit,
+            None => return Colour::black(),
+        };
 
-            for object in objects.iter() {
-                if let Some(collision) = object.collides_with(&ray).first() {
-                    let mut intensity = 0;
+        let material = &hit.material;
 
-                    for light in lights.iter() {
-                        let mut dir = light.position.clone();
-                        let origin = collision.clone();
-                        dir.minus(&collision);
-                        let light_distance = dir.magnitude();
-                        dir.normalise();
+        let mut view = ray.direction.clone();
+        view.normalise();
+        view.mult(-1.0);
 
-                        let ray = Ray::new(origin, dir);
+        let mut colour = Camera::shade(&hit, &view, objects, lights, ray.time);
 
-                        intensity = Camera::cast_ray(&ray, light_distance, &objects);
-                    }
+        if material.reflectivity <= 0.0 && material.transparency <= 0.0 {
+            return colour;
+        }
 
-                    canvas.ink(x, y, intensity);
-                    break;
+        let mut direction = ray.direction.clone();
+        direction.normalise();
+        let normal = &hit.normal;
+
+        let reflected_dir = direction.reflect(normal);
+        let mut reflect_origin = hit.point.clone();
+        let mut bias = normal.clone();
+        bias.mult(EPSILON);
+        reflect_origin.add(&bias);
+        let reflected = self.trace(
+            &Ray::new(reflect_origin, reflected_dir).with_time(ray.time),
+            depth - 1,
+            objects,
+            lights,
+        );
+
+        if material.transparency > 0.0 {
+            let (outward, ni_over_nt, cosine) = if direction.dot(normal) < 0.0 {
+                (normal.clone(), 1.0 / material.refractive_index, -direction.dot(normal))
+            } else {
+                let mut flipped = normal.clone();
+                flipped.mult(-1.0);
+                (
+                    flipped,
+                    material.refractive_index,
+                    material.refractive_index * direction.dot(normal),
+                )
+            };
+
+            let transmitted = match direction.refract(&outward, ni_over_nt) {
+                Some(refracted_dir) => {
+                    let mut refract_origin = hit.point.clone();
+                    let mut into = outward.clone();
+                    into.mult(-EPSILON);
+                    refract_origin.add(&into);
+                    self.trace(
+                        &Ray::new(refract_origin, refracted_dir).with_time(ray.time),
+                        depth - 1,
+                        objects,
+                        lights,
+                    )
                 }
+                None => reflected.clone(),
+            };
+
+            let reflectance = schlick(cosine, material.refractive_index);
+            let blended = reflected
+                .scale(reflectance)
+                .add(&transmitted.scale(1.0 - reflectance));
+            colour = colour
+                .scale(1.0 - material.transparency)
+                .add(&blended.scale(material.transparency));
+        } else {
+            colour = colour
+                .scale(1.0 - material.reflectivity)
+                .add(&reflected.scale(material.reflectivity));
+        }
+
+        colour
+    }
+
+    /// Blinn-Phong shading at a hit point: a constant ambient term plus, for
+    /// every unoccluded light, a diffuse lobe and a specular highlight summed
+    /// over the surface.
+    fn shade(hit: &Hit, view: &Vector, objects: &[Box<dyn Hittable>], lights: &[Light], time: f64) -> Colour {
+        let material = &hit.material;
+        let normal = &hit.normal;
+
+        let mut colour = material.colour.scale(material.ambient);
+
+        for light in lights.iter() {
+            let mut l = light.position.clone();
+            l.minus(&hit.point);
+            let light_distance = l.magnitude();
+            l.normalise();
+
+            if Camera::occluded(&hit.point, &l, light_distance, objects, time) {
+                continue;
             }
 
-            ray.origin.set(0.0, 0.0, 0.0);
-            offset_x.set_as(&self.plane_x);
-            offset_y.set_as(&plane_y);
+            let n_dot_l = normal.dot(&l).max(0.0);
+            let diffuse = material
+                .colour
+                .mult(&light.colour)
+                .scale(material.diffuse * n_dot_l * light.intensity);
+            colour = colour.add(&diffuse);
+
+            let mut half = l.clone();
+            half.add(view);
+            half.normalise();
+
+            let n_dot_h = normal.dot(&half).max(0.0);
+            let specular = light
+                .colour
+                .scale(material.specular * n_dot_h.powf(material.shininess) * light.intensity);
+            colour = colour.add(&specular);
+        }
+
+        colour
+    }
+
+    pub fn raytrace(&self, objects: &[Box<dyn Hittable>], lights: &[Light]) -> Canvas {
+        let mut canvas = Canvas::new(self.pixels_width, self.pixels_height);
+
+        let basis = self.basis();
+
+        let chunk_rows = self.chunk_rows.max(1);
+        let tile = chunk_rows * self.pixels_width;
+        let samples = self.samples.max(1);
+
+        let mut render = || {
+            canvas
+                .data
+                .par_chunks_mut(tile)
+                .enumerate()
+                .for_each(|(chunk, pixels)| {
+                    let base = chunk * tile;
+                    let mut rng = rand::thread_rng();
+                    for (offset, pixel) in pixels.iter_mut().enumerate() {
+                        let index = base + offset;
+                        let x = index % self.pixels_width;
+                        let y = index / self.pixels_width;
+
+                        let mut colour = Colour::black();
+                        for _ in 0..samples {
+                            let (jx, jy) = if samples == 1 {
+                                (0.5, 0.5)
+                            } else {
+                                (rng.gen::<f64>(), rng.gen::<f64>())
+                            };
+
+                            let s = ((x as f64) + jx) / (self.pixels_width as f64);
+                            let t = (((self.pixels_height - 1 - y) as f64) + jy)
+                                / (self.pixels_height as f64);
+
+                            let time = if self.shutter_close > self.shutter_open {
+                                self.shutter_open
+                                    + rng.gen::<f64>() * (self.shutter_close - self.shutter_open)
+                            } else {
+                                self.shutter_open
+                            };
+
+                            let ray = basis.ray(s, t, &mut rng).with_time(time);
+                            colour = colour.add(&self.trace(&ray, self.max_depth, objects, lights));
+                        }
+
+                        *pixel = colour.scale(1.0 / (samples as f64));
+                    }
+                });
+        };
+
+        if self.threads > 0 {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(self.threads)
+                .build()
+                .unwrap();
+            pool.install(render);
+        } else {
+            render();
         }
 
         canvas
@@ -351,6 +978,7 @@ impl Camera {
 struct Light {
     position: Vector,
     intensity: f64,
+    colour: Colour,
 }
 
 impl Light {
@@ -358,12 +986,18 @@ impl Light {
         Light {
             position,
             intensity,
+            colour: Colour::white(),
         }
     }
+
+    pub fn with_colour(&mut self, colour: Colour) -> &mut Self {
+        self.colour = colour;
+        self
+    }
 }
 
 struct Scene {
-    objects: Vec<Sphere>,
+    objects: Vec<Box<dyn Hittable>>,
     camera: Camera,
     lights: Vec<Light>,
 }
@@ -377,7 +1011,7 @@ impl Scene {
         }
     }
 
-    pub fn add_object(&mut self, o: Sphere) -> &mut Self {
+    pub fn add_object(&mut self, o: Box<dyn Hittable>) -> &mut Self {
         self.objects.push(o);
         self
     }
@@ -392,23 +1026,90 @@ impl Scene {
     }
 }
 
-fn main() {
-    let sphere1 = Sphere::new(Vector::new(5.0, 5.0, 5.0), 2.0);
-    let sphere2 = Sphere::new(Vector::new(7.0, 7.0, 5.0), 0.2);
-    let sphere3 = Sphere::new(Vector::new(3.0, 3.0, 5.0), 0.5);
-
-    let light = Light::new(Vector::new(5.0, 9.0, 1.0), 1.0);
-
-    let camera = Camera::new(10.0, 10.0, 1024, 1024);
+/// Build the demo scene — a matte, a reflective (moving) and a glass sphere
+/// over a grey floor with a triangle — viewed through the given camera. Taking
+/// the camera lets the same set-up be rendered from different projections.
+fn demo_scene(camera: Camera) -> Scene {
+    let mut matte = Material::new(Colour::new(0.8, 0.2, 0.2));
+    matte
+        .with_ambient(0.1)
+        .with_diffuse(0.9)
+        .with_specular(0.4)
+        .with_shininess(50.0);
+    let sphere1 = Sphere::new(Vector::new(5.0, 5.0, 5.0), 2.0, matte);
+
+    let mut mirror = Material::new(Colour::new(0.2, 0.8, 0.2));
+    mirror.with_reflectivity(0.4);
+    let mut sphere2 = Sphere::new(Vector::new(7.0, 7.0, 5.0), 0.2, mirror);
+    sphere2.with_motion(Vector::new(7.0, 7.5, 5.0), 0.0, 1.0);
+
+    let mut glass = Material::new(Colour::new(0.2, 0.2, 0.8));
+    glass.with_transparency(0.9, 1.5);
+    let sphere3 = Sphere::new(Vector::new(3.0, 3.0, 5.0), 0.5, glass);
+
+    let floor = Plane::new(
+        Vector::new(0.0, 0.0, 0.0),
+        Vector::new(0.0, 1.0, 0.0),
+        Material::new(Colour::new(0.6, 0.6, 0.6)),
+    );
+    let triangle = Triangle::new(
+        Vector::new(1.0, 1.0, 6.0),
+        Vector::new(3.0, 1.0, 6.0),
+        Vector::new(2.0, 3.0, 6.0),
+        Material::new(Colour::new(0.8, 0.8, 0.2)),
+    );
+
+    let mut light = Light::new(Vector::new(5.0, 9.0, 1.0), 1.0);
+    light.with_colour(Colour::new(1.0, 1.0, 0.9));
 
     let mut scene = Scene::new(camera);
     scene
-        .add_object(sphere1)
-        .add_object(sphere2)
-        .add_object(sphere3)
+        .add_object(Box::new(sphere1))
+        .add_object(Box::new(sphere2))
+        .add_object(Box::new(sphere3))
+        .add_object(Box::new(floor))
+        .add_object(Box::new(triangle))
         .add_light(light);
+    scene
+}
 
-    let canvas = scene.raytrace();
-
-    render_ppm(Path::new("/tmp/out.pbm"), canvas);
+fn main() {
+    let mut camera = Camera::new(
+        Vector::new(5.0, 5.0, -5.0),
+        Vector::new(5.0, 5.0, 5.0),
+        60.0,
+        1024,
+        1024,
+    );
+    camera
+        .with_vup(Vector::new(0.0, 1.0, 0.0))
+        .with_projection(Projection::Perspective)
+        .with_focus(0.1, 10.0)
+        .with_max_depth(8)
+        .with_threads(0)
+        .with_chunk_rows(16)
+        .with_samples(16)
+        .with_shutter(0.0, 1.0);
+    render_ppm(
+        Path::new("/tmp/out.ppm"),
+        demo_scene(camera).raytrace(),
+        PpmFormat::Ascii,
+    );
+
+    // The same set-up through an orthographic lens, written as binary PPM.
+    let mut ortho = Camera::new(
+        Vector::new(5.0, 5.0, -5.0),
+        Vector::new(5.0, 5.0, 5.0),
+        60.0,
+        512,
+        512,
+    );
+    ortho
+        .with_projection(Projection::Orthographic)
+        .with_focus(0.0, 12.0);
+    render_ppm(
+        Path::new("/tmp/out-ortho.ppm"),
+        demo_scene(ortho).raytrace(),
+        PpmFormat::Binary,
+    );
 }